@@ -1,6 +1,10 @@
-use btleplug::api::{BDAddr, Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
-use btleplug::platform::{Manager, Peripheral};
-use futures::stream::StreamExt;
+use async_stream::stream;
+use btleplug::api::{
+    BDAddr, Central, Characteristic, CentralEvent, Manager as _, Peripheral as _, ScanFilter,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::{Stream, StreamExt};
 use std::time;
 use uuid::{uuid, Uuid};
 
@@ -8,6 +12,17 @@ const ARANET4_SERVICE: Uuid = uuid!("0000fce0-0000-1000-8000-00805f9b34fb");
 
 const ARANET4_CHARACTERISTIC: Uuid = uuid!("f0cd3001-95da-4f4b-9ac8-aa55d312af0c");
 
+const ARANET4_TOTAL_READINGS_CHARACTERISTIC: Uuid =
+    uuid!("f0cd2001-95da-4f4b-9ac8-aa55d312af0c");
+const ARANET4_INTERVAL_CHARACTERISTIC: Uuid = uuid!("f0cd2002-95da-4f4b-9ac8-aa55d312af0c");
+const ARANET4_AGO_CHARACTERISTIC: Uuid = uuid!("f0cd2004-95da-4f4b-9ac8-aa55d312af0c");
+const ARANET4_HISTORY_CHARACTERISTIC: Uuid = uuid!("f0cd2005-95da-4f4b-9ac8-aa55d312af0c");
+const ARANET4_COMMAND_CHARACTERISTIC: Uuid = uuid!("f0cd1402-95da-4f4b-9ac8-aa55d312af0c");
+
+const ARANET4_MANUFACTURER_ID: u16 = 0x0702;
+
+const ARANET4_SET_INTERVAL_COMMAND: u8 = 0x90;
+
 const BLUETOOTH_MODEL_NUMBER_CHARACTERISTIC: Uuid = uuid!("00002a24-0000-1000-8000-00805f9b34fb");
 const BLUETOOTH_SERIAL_NUMBER_CHARACTERISTIC: Uuid = uuid!("00002a25-0000-1000-8000-00805f9b34fb");
 const BLUETOOTH_FIRMWARE_REVISION_CHARACTERISTIC: Uuid =
@@ -69,24 +84,256 @@ pub struct Info {
     pub manufacturer_name: Option<String>,
 }
 
+/// A logged parameter that can be requested from a device's on-board history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum Param {
+    Co2 = 1,
+    Temperature = 2,
+    Pressure = 3,
+    Humidity = 4,
+}
+
+/// A single historical sample read from the device's on-board log.
+///
+/// Only the fields for the [`Param`]s that were requested from [`get_history`] are populated.
+#[derive(Debug, serde::Serialize)]
+pub struct Record {
+    pub timestamp: time::SystemTime,
+    pub co2: Option<u16>,
+    pub temperature: Option<f32>,
+    pub pressure: Option<f32>,
+    pub humidity: Option<u8>,
+}
+
+/// Downloads the on-board history log for the requested `params`.
+///
+/// Unlike [`get_data`] this does not return the current live reading but the thousands of
+/// samples the device keeps logging at its configured interval, oldest first.
+///
+/// # Arguments
+///
+/// * `device` - A connected peripheral with services already discovered.
+/// * `params` - Which logged parameters to download.
+pub async fn get_history(device: &Peripheral, params: &[Param]) -> anyhow::Result<Vec<Record>> {
+    let chars = device.characteristics();
+    let total_readings_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_TOTAL_READINGS_CHARACTERISTIC)
+        .unwrap();
+    let interval_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_INTERVAL_CHARACTERISTIC)
+        .unwrap();
+    let ago_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_AGO_CHARACTERISTIC)
+        .unwrap();
+    let command_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_COMMAND_CHARACTERISTIC)
+        .unwrap();
+    let history_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_HISTORY_CHARACTERISTIC)
+        .unwrap();
+
+    let total_readings = u16::from_le_bytes(
+        device.read(total_readings_char).await?[0..2].try_into()?,
+    ) as usize;
+    let interval = time::Duration::from_secs(
+        u16::from_le_bytes(device.read(interval_char).await?[0..2].try_into()?) as u64,
+    );
+    let ago = time::Duration::from_secs(
+        u16::from_le_bytes(device.read(ago_char).await?[0..2].try_into()?) as u64,
+    );
+    let now = time::SystemTime::now();
+
+    device.subscribe(history_char).await?;
+    let mut notifications = device.notifications().await?;
+
+    // Collected as a single result so a failure partway through the loop below still leaves the
+    // device unsubscribed afterwards, instead of leaking a subscription that would confuse a
+    // subsequent retry.
+    let columns = async {
+        let mut co2 = None;
+        let mut temperature = None;
+        let mut pressure = None;
+        let mut humidity = None;
+        for &param in params {
+            let values = get_history_param(
+                device,
+                command_char,
+                history_char,
+                &mut notifications,
+                param,
+                total_readings,
+            )
+            .await?;
+            match param {
+                Param::Co2 => co2 = Some(values),
+                Param::Temperature => temperature = Some(values),
+                Param::Pressure => pressure = Some(values),
+                Param::Humidity => humidity = Some(values),
+            }
+        }
+        anyhow::Ok((co2, temperature, pressure, humidity))
+    }
+    .await;
+
+    device.unsubscribe(history_char).await?;
+    let (co2, temperature, pressure, humidity) = columns?;
+
+    let mut records = Vec::with_capacity(total_readings);
+    for index in 0..total_readings {
+        let age = ago + interval * (total_readings - 1 - index) as u32;
+        records.push(Record {
+            timestamp: now.checked_sub(age).unwrap(),
+            co2: co2.as_ref().map(|values| values[index] as u16),
+            temperature: temperature.as_ref().map(|values| values[index] as f32),
+            pressure: pressure.as_ref().map(|values| values[index] as f32),
+            humidity: humidity.as_ref().map(|values| values[index] as u8),
+        });
+    }
+
+    Ok(records)
+}
+
+/// Requests and reassembles a single logged parameter's column of history values.
+async fn get_history_param(
+    device: &Peripheral,
+    command_char: &Characteristic,
+    history_char: &Characteristic,
+    notifications: &mut (impl futures::stream::Stream<Item = btleplug::api::ValueNotification> + Unpin),
+    param: Param,
+    total_readings: usize,
+) -> anyhow::Result<Vec<f64>> {
+    let value_size = if param == Param::Humidity { 1 } else { 2 };
+
+    let start = 0u16.to_le_bytes();
+    let command = [0x61, param as u8, 0x00, 0x00, 0x01, 0x00, start[0], start[1]];
+    device
+        .write(command_char, &command, WriteType::WithoutResponse)
+        .await?;
+
+    let mut values = vec![0.0; total_readings];
+    let mut received = 0;
+    while received < total_readings {
+        let notification = notifications
+            .next()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("device stopped sending history notifications"))?;
+        if notification.uuid != history_char.uuid || notification.value[0] != param as u8 {
+            continue;
+        }
+
+        let data = &notification.value;
+        let start = u16::from_le_bytes(data[1..3].try_into()?) as usize;
+        let count = data[3] as usize;
+        for i in 0..count {
+            let offset = 4 + i * value_size;
+            let raw = if value_size == 2 {
+                u16::from_le_bytes(data[offset..offset + 2].try_into()?) as f64
+            } else {
+                data[offset] as f64
+            };
+            values[start + i] = match param {
+                Param::Temperature => raw / 20.0,
+                Param::Pressure => raw / 10.0,
+                Param::Co2 | Param::Humidity => raw,
+            };
+        }
+
+        received = received.max(start + count);
+    }
+
+    Ok(values)
+}
+
+/// Sets the device's measurement/logging interval.
+///
+/// Re-reads the interval characteristic afterwards and errors out if the device did not apply
+/// the requested value.
+pub async fn set_interval(device: &Peripheral, interval: time::Duration) -> anyhow::Result<()> {
+    if interval.as_secs() > u16::MAX as u64 {
+        anyhow::bail!(
+            "interval of {}s does not fit the device's 16-bit seconds field (max {}s)",
+            interval.as_secs(),
+            u16::MAX
+        );
+    }
+
+    let chars = device.characteristics();
+    let command_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_COMMAND_CHARACTERISTIC)
+        .unwrap();
+    let interval_char = chars
+        .iter()
+        .find(|c| c.uuid == ARANET4_INTERVAL_CHARACTERISTIC)
+        .unwrap();
+
+    let seconds = interval.as_secs() as u16;
+    let seconds_bytes = seconds.to_le_bytes();
+    let command = [
+        ARANET4_SET_INTERVAL_COMMAND,
+        seconds_bytes[0],
+        seconds_bytes[1],
+    ];
+    device
+        .write(command_char, &command, WriteType::WithResponse)
+        .await?;
+
+    let written = u16::from_le_bytes(device.read(interval_char).await?[0..2].try_into()?);
+    if written != seconds {
+        anyhow::bail!(
+            "failed to set interval: device reports {written}s, expected {seconds}s"
+        );
+    }
+
+    Ok(())
+}
+
+// Toggling "Smart Home Integration" (the advertisement broadcast parsed by [`get_devices`]'s
+// `no_connect` path) also goes through the command characteristic, but unlike the interval
+// command above there is no confirmed opcode/packet shape for it and no readback to validate
+// a guess against real hardware. Left out until that's sourced rather than shipping a write to
+// the device based on an invented command byte.
+
 /// Scans for all Aranet4 devices for a given `timeout`.
 ///
 /// # Arguments
 ///
 /// * `max_devices` - Optional maximum number of devices to wait for before quitting early.
 /// * `timeout` - Maximum time to wait for devices to be discovered before returning.
+/// * `no_connect` - If set, never connects to a device. Instead the current reading is parsed
+///   straight out of the scan's advertisement data, which only works for devices that broadcast
+///   it (Aranet4's "Smart Home Integration" setting). `Device::info` is left at its default in
+///   this mode since the info characteristics require a connection to read. Note that Smart Home
+///   Integration itself can only be turned on from the device's own menu today — this crate has
+///   no `set_smart_home_integration` counterpart to `set_interval` yet, since the write
+///   command's opcode isn't confirmed against real hardware.
+/// * `adapter` - Name of the Bluetooth adapter to use, as reported by `Adapter::adapter_info`.
+///   Defaults to the first adapter the platform reports when not given.
 pub async fn get_devices(
     max_devices: Option<usize>,
     timeout: time::Duration,
+    no_connect: bool,
+    adapter: Option<String>,
 ) -> anyhow::Result<Vec<Device>> {
     let manager = Manager::new().await.unwrap();
 
-    // Get the first bluetooth adapter.
-    let adapters = manager.adapters().await?;
-    let central = adapters.into_iter().next().unwrap();
+    let central = get_adapter(&manager, adapter.as_deref()).await?;
 
-    let scan_filter = ScanFilter {
-        services: vec![ARANET4_SERVICE],
+    // A device advertising only via the Smart-Home-Integration manufacturer record is unlikely
+    // to also carry the service UUID in the same advertising PDU, and some backends enforce
+    // `ScanFilter` at the OS level rather than treating it as best effort — filtering on the
+    // service here would mean such devices never even generate a `DeviceDiscovered` event.
+    let scan_filter = if no_connect {
+        ScanFilter::default()
+    } else {
+        ScanFilter {
+            services: vec![ARANET4_SERVICE],
+        }
     };
     central.start_scan(scan_filter).await?;
     let mut events = central.events().await?;
@@ -101,13 +348,26 @@ pub async fn get_devices(
     {
         if let CentralEvent::DeviceDiscovered(id) = event {
             let device = central.peripheral(&id).await.unwrap();
-            let services = get_services(&device).await?;
-            // The ScanFilter is only best effort and some implementation might return devices that
-            // do not offer the requested service.
-            if !services.contains(&ARANET4_SERVICE) {
-                continue;
+
+            if !no_connect {
+                let services = get_services(&device).await?;
+                // The ScanFilter is only best effort and some implementation might return
+                // devices that do not offer the requested service.
+                if !services.contains(&ARANET4_SERVICE) {
+                    continue;
+                }
             }
-            devices.push(get_device(&device).await?);
+
+            let found = if no_connect {
+                get_device_from_advertisement(&device).await?
+            } else {
+                Some(get_device(&device).await?)
+            };
+            let found = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            devices.push(found);
 
             if !max_devices.map(|m| devices.len() < m).unwrap_or(true) {
                 return Ok(devices);
@@ -119,6 +379,109 @@ pub async fn get_devices(
     Ok(devices)
 }
 
+/// Resolves the adapter named `adapter`, or the first available one when `None`.
+async fn get_adapter(manager: &Manager, adapter: Option<&str>) -> anyhow::Result<Adapter> {
+    let adapters = manager.adapters().await?;
+
+    let adapter_name = match adapter {
+        Some(adapter_name) => adapter_name,
+        None => {
+            return adapters
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("no Bluetooth adapters found"));
+        }
+    };
+
+    let mut available = Vec::new();
+    for candidate in adapters {
+        let info = candidate.adapter_info().await?;
+        if info == adapter_name {
+            return Ok(candidate);
+        }
+        available.push(info);
+    }
+
+    anyhow::bail!(
+        "no Bluetooth adapter named \"{adapter_name}\" found, available adapters: [{}]",
+        available.join(", ")
+    )
+}
+
+/// How long to wait between reconnect attempts after one fails, so a persistent failure (e.g. a
+/// yanked adapter or Bluetooth powered off) doesn't spin a core retrying forever.
+const WATCH_RECONNECT_BACKOFF: time::Duration = time::Duration::from_secs(5);
+
+/// Continuously emits live readings for the device at `address`, polling it every `interval`.
+///
+/// Unlike [`get_devices`]/[`get_device`] this does not give up after a single scan-and-read:
+/// if the peripheral disconnects, the returned stream re-scans for it and reconnects
+/// transparently instead of ending, so a long-lived consumer (e.g. piping into a dashboard)
+/// never has to restart the process. A reconnect failure is surfaced as an `Err` item (so a
+/// consumer can tell the device has gone away for a while) and followed by a backoff before the
+/// stream tries again, rather than ending or retrying in a tight loop.
+pub fn watch(address: BDAddr, interval: time::Duration) -> impl Stream<Item = anyhow::Result<Data>> {
+    stream! {
+        let manager = Manager::new().await.unwrap();
+        let central = match get_adapter(&manager, None).await {
+            Ok(central) => central,
+            Err(err) => {
+                yield Err(err);
+                return;
+            }
+        };
+
+        loop {
+            let reconnected = async {
+                let device = find_peripheral(&central, address).await?;
+                device.connect().await?;
+                device.discover_services().await?;
+                anyhow::Ok(device)
+            }
+            .await;
+            let device = match reconnected {
+                Ok(device) => device,
+                Err(err) => {
+                    yield Err(err);
+                    tokio::time::sleep(WATCH_RECONNECT_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            loop {
+                let reading = match get_data(&device).await {
+                    Ok(reading) => reading,
+                    // The device went away; break out to re-discover and reconnect to it.
+                    Err(_) => break,
+                };
+                yield Ok(reading);
+                tokio::time::sleep(interval).await;
+            }
+        }
+    }
+}
+
+/// Scans until a peripheral with the given `address` is discovered.
+async fn find_peripheral(central: &Adapter, address: BDAddr) -> anyhow::Result<Peripheral> {
+    let scan_filter = ScanFilter {
+        services: vec![ARANET4_SERVICE],
+    };
+    central.start_scan(scan_filter).await?;
+    let mut events = central.events().await?;
+
+    while let Some(event) = events.next().await {
+        if let CentralEvent::DeviceDiscovered(id) = event {
+            let device = central.peripheral(&id).await?;
+            if device.address() == address {
+                central.stop_scan().await?;
+                return Ok(device);
+            }
+        }
+    }
+
+    anyhow::bail!("adapter stopped reporting discoveries before {address} was found")
+}
+
 async fn get_device(aranet_device: &Peripheral) -> anyhow::Result<Device> {
     aranet_device.connect().await?;
     aranet_device.discover_services().await?;
@@ -131,6 +494,49 @@ async fn get_device(aranet_device: &Peripheral) -> anyhow::Result<Device> {
     })
 }
 
+/// Builds a `Device` straight from the scan's advertisement data, without ever connecting.
+///
+/// Returns `Ok(None)` when the device did not advertise an Aranet4 manufacturer record, or it
+/// could not be decoded, e.g. because "Smart Home Integration" is disabled on the device.
+async fn get_device_from_advertisement(device: &Peripheral) -> anyhow::Result<Option<Device>> {
+    let properties = device.properties().await?.unwrap();
+    let data = match parse_advertisement_data(&properties.manufacturer_data) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Device {
+        name: properties.local_name.unwrap_or_default(),
+        address: device.address(),
+        data,
+        info: Info::default(),
+    }))
+}
+
+/// Parses a live `Data` reading out of Aranet4's manufacturer-specific advertisement record.
+fn parse_advertisement_data(
+    manufacturer_data: &std::collections::HashMap<u16, Vec<u8>>,
+) -> Option<Data> {
+    const HEADER_LEN: usize = 6;
+
+    let payload = manufacturer_data.get(&ARANET4_MANUFACTURER_ID)?;
+    if payload.len() < HEADER_LEN + 13 {
+        return None;
+    }
+    let res = &payload[HEADER_LEN..];
+
+    Some(Data {
+        co2: u16::from_le_bytes(res[0..2].try_into().ok()?),
+        temperature: u16::from_le_bytes(res[2..4].try_into().ok()?) as f32 / 20.0,
+        pressure: u16::from_le_bytes(res[4..6].try_into().ok()?) as f32 / 10.0,
+        humidity: u8::from_le(res[6]),
+        battery: u8::from_le(res[7]),
+        status: u8::from_le(res[8]).try_into().ok()?,
+        interval: time::Duration::from_secs(u16::from_le_bytes(res[9..11].try_into().ok()?) as u64),
+        ago: time::Duration::from_secs(u16::from_le_bytes(res[11..13].try_into().ok()?) as u64),
+    })
+}
+
 async fn get_name(device: &Peripheral) -> anyhow::Result<String> {
     let properties = device.properties().await?.unwrap();
     Ok(properties.local_name.unwrap())